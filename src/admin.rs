@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::sync::Mutex;
+use headless_chrome::protocol::network::events::ResponseReceivedEventParams;
+use headless_chrome::Tab;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use subtle::ConstantTimeEq;
+
+use crate::core::Handler;
+
+const LOGIN_URL: &str = "https://accounts.kakao.com/login?continue=https%3A%2F%2Faccounts.kakao.com%2Fweblogin%2Faccount%2Finfo";
+const CHALLENGE_SELECTOR: &str = "input[name=\"otp\"], input[name=\"verify_code\"], button.btn_confirm";
+const CHALLENGE_WAIT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, PartialEq)]
+enum AdminState {
+    Idle,
+    AwaitingChallenge,
+    LoggedIn,
+}
+
+/// Holds the in-progress interactive Kakao login session driven from the admin
+/// website, so that a follow-up challenge (OTP, new-device verification) can be
+/// relayed back to whichever tab is already mid-login.
+pub struct AdminSession {
+    state: Mutex<AdminState>,
+    tab: Mutex<Option<Arc<Tab>>>,
+}
+
+impl AdminSession {
+    pub fn new() -> Self {
+        AdminSession {
+            state: Mutex::new(AdminState::Idle),
+            tab: Mutex::new(None),
+        }
+    }
+}
+
+fn resp(status: StatusCode) -> hyper::Result<Response<Body>> {
+    Ok(Response::builder().status(status).body(Body::default()).unwrap())
+}
+
+fn html(body: &str) -> hyper::Result<Response<Body>> {
+    Ok(
+        Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+    )
+}
+
+/// Compares in constant time: this secret gates a flow that can log a real
+/// Kakao identity into a headless browser and persist its session cookies, so
+/// a timing side-channel here is materially more sensitive than on the old
+/// `X-API-KEY` check it's modeled on.
+fn check_secret(handler: &Handler, provided: &str) -> bool {
+    let expected = handler.admin_secret().as_bytes();
+    let provided = provided.as_bytes();
+
+    !provided.is_empty() && expected.len() == provided.len() && bool::from(expected.ct_eq(provided))
+}
+
+/// Routes requests under `/admin`: the login form, the credential submission
+/// that drives headless Chrome, and the follow-up challenge (OTP / device
+/// verification) relay. `route_path` has any leading `/<account>` segment
+/// already stripped by `Handler::resolve_account`.
+pub async fn handle(handler: Arc<Handler>, req: Request<Body>, account_id: String, route_path: String) -> hyper::Result<Response<Body>> {
+    if handler.account(&account_id).is_none() {
+        return resp(StatusCode::NOT_FOUND)
+    }
+
+    let method = req.method().clone();
+
+    match (method, route_path.as_str()) {
+        (Method::GET, "/admin") => html(LOGIN_PAGE),
+        (Method::POST, "/admin/login") => start_login(handler, req, account_id).await,
+        (Method::POST, "/admin/challenge") => submit_challenge(handler, req, account_id).await,
+        _ => resp(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn read_form(req: Request<Body>) -> Option<HashMap<String, String>> {
+    let body = hyper::body::to_bytes(req.into_body()).await.ok()?;
+    Some(url::form_urlencoded::parse(&body).into_owned().collect())
+}
+
+/// Returns true if a challenge screen (OTP entry, "verify this device", ...)
+/// is currently showing in the given tab.
+fn has_pending_challenge(tab: &Tab) -> bool {
+    tab.wait_for_element_with_custom_timeout(CHALLENGE_SELECTOR, CHALLENGE_WAIT).is_ok()
+}
+
+async fn start_login(handler: Arc<Handler>, req: Request<Body>, account_id: String) -> hyper::Result<Response<Body>> {
+    let form = match read_form(req).await {
+        Some(x) => x,
+        None => return resp(StatusCode::BAD_REQUEST),
+    };
+
+    let secret = form.get("secret").map(String::as_str).unwrap_or("");
+    if !check_secret(&handler, secret) {
+        return resp(StatusCode::UNAUTHORIZED)
+    }
+
+    let account = match handler.account(&account_id) {
+        Some(x) => x,
+        None => return resp(StatusCode::NOT_FOUND),
+    };
+
+    let id = form.get("id").cloned().filter(|x| !x.is_empty()).or_else(|| account.kakao_id.clone()).unwrap_or_default();
+    let pw = form.get("pw").cloned().filter(|x| !x.is_empty()).or_else(|| account.kakao_pw.clone()).unwrap_or_default();
+
+    let cookiejar = account.cookiejar.clone();
+    let tab = {
+        let browser = handler.browser().lock().await;
+        match browser.new_tab() {
+            Ok(x) => x,
+            Err(err) => {
+                println!("Error opening admin login tab: {}", err);
+                return resp(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    };
+
+    tab.set_default_timeout(Duration::from_secs(30));
+    let enable_response_handling = tab.enable_response_handling(
+        Box::new(
+            move |resp_event: ResponseReceivedEventParams, _| {
+                let url = match url::Url::parse(resp_event.response.url.as_str()) {
+                    Ok(x) => x,
+                    Err(_) => return,
+                };
+
+                for (k, v) in resp_event.response.headers.iter() {
+                    if k.to_lowercase() == "set-cookie" {
+                        cookiejar.add_cookie_str(v, &url);
+                    }
+                }
+            }
+        )
+    );
+    if let Err(err) = enable_response_handling {
+        println!("Error watching admin login cookies: {}", err);
+        return resp(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    if let Err(err) = tab.navigate_to(LOGIN_URL) {
+        println!("Error navigating to Kakao login: {}", err);
+        return resp(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+    if let Err(err) = tab.wait_for_element("#login-form") {
+        println!("Error waiting for Kakao login form: {}", err);
+        return resp(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    let js = format!(
+        " \
+            document.getElementById('id_email_2').value = '{}'; \
+            document.getElementById('id_password_3').value = '{}'; \
+        ",
+        id,
+        pw,
+    );
+    if let Err(err) = tab.evaluate(js.as_str(), true) {
+        println!("Error filling admin login form: {}", err);
+        return resp(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    match tab.wait_for_element("form#login-form button.submit").and_then(|el| el.click()) {
+        Ok(_) => {},
+        Err(err) => {
+            println!("Error submitting admin login form: {}", err);
+            return resp(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+
+    let _ = tab.wait_until_navigated();
+
+    let session = &account.admin_session;
+    *session.tab.lock().await = Some(tab.clone());
+
+    if has_pending_challenge(&tab) {
+        *session.state.lock().await = AdminState::AwaitingChallenge;
+        html(CHALLENGE_PAGE)
+    } else {
+        *session.state.lock().await = AdminState::LoggedIn;
+        html(SUCCESS_PAGE)
+    }
+}
+
+async fn submit_challenge(handler: Arc<Handler>, req: Request<Body>, account_id: String) -> hyper::Result<Response<Body>> {
+    let form = match read_form(req).await {
+        Some(x) => x,
+        None => return resp(StatusCode::BAD_REQUEST),
+    };
+
+    let secret = form.get("secret").map(String::as_str).unwrap_or("");
+    if !check_secret(&handler, secret) {
+        return resp(StatusCode::UNAUTHORIZED)
+    }
+
+    let account = match handler.account(&account_id) {
+        Some(x) => x,
+        None => return resp(StatusCode::NOT_FOUND),
+    };
+
+    let session = &account.admin_session;
+    if *session.state.lock().await != AdminState::AwaitingChallenge {
+        return resp(StatusCode::BAD_REQUEST)
+    }
+
+    let tab = match session.tab.lock().await.clone() {
+        Some(x) => x,
+        None => return resp(StatusCode::BAD_REQUEST),
+    };
+
+    let code = form.get("code").cloned().unwrap_or_default();
+    if !code.is_empty() {
+        let js = format!(
+            "var el = document.querySelector('input[name=\"otp\"], input[name=\"verify_code\"]'); if (el) {{ el.value = '{}'; }}",
+            code,
+        );
+        let _ = tab.evaluate(js.as_str(), true);
+    }
+
+    if let Ok(button) = tab.find_element("button.btn_confirm, form button[type=\"submit\"]") {
+        let _ = button.click();
+    }
+    let _ = tab.wait_until_navigated();
+
+    if has_pending_challenge(&tab) {
+        html(CHALLENGE_PAGE)
+    } else {
+        *session.state.lock().await = AdminState::LoggedIn;
+        html(SUCCESS_PAGE)
+    }
+}
+
+const LOGIN_PAGE: &str = r#"<!doctype html>
+<html><body>
+<h1>Kakao login</h1>
+<form method="post" action="/admin/login">
+  <input type="password" name="secret" placeholder="Admin secret"><br>
+  <input type="text" name="id" placeholder="Kakao ID"><br>
+  <input type="password" name="pw" placeholder="Kakao password"><br>
+  <button type="submit">Log in</button>
+</form>
+</body></html>"#;
+
+const CHALLENGE_PAGE: &str = r#"<!doctype html>
+<html><body>
+<h1>Additional verification required</h1>
+<p>Check your phone for an OTP or device-verification prompt, then continue below.</p>
+<form method="post" action="/admin/challenge">
+  <input type="password" name="secret" placeholder="Admin secret"><br>
+  <input type="text" name="code" placeholder="OTP (leave blank if none)"><br>
+  <button type="submit">Continue</button>
+</form>
+</body></html>"#;
+
+const SUCCESS_PAGE: &str = r#"<!doctype html>
+<html><body>
+<h1>Logged in</h1>
+<p>Kakao session cookies were saved. The server will keep refreshing the token from here.</p>
+</body></html>"#;
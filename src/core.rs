@@ -15,24 +15,92 @@ use qrcode_generator::QrCodeEcc;
 use regex::bytes::Regex;
 use reqwest::{Client, cookie::Jar};
 use serde_derive::Deserialize;
+use tokio::sync::broadcast;
 
+use crate::admin::{self, AdminSession};
 use crate::err::HandlerError;
+use crate::jwt_auth::JwtValidator;
+use crate::signing::Signer;
+use crate::ws;
 
 const USER_AGENT: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 14_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148 KAKAOTALK 9.0.3";
 
 const DEFAULT_PNG_SIZE: u16 = 256; // Pixel
 const TOKEN_EXPIRES: f32 = 14_f32; // Seconds
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_millis(500); // Refresh slightly before expiry
+const BROADCAST_CHANNEL_CAPACITY: usize = 16;
+const TOKEN_FETCH_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// A freshly-rotated token, broadcast to subscribed WebSocket clients.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenUpdate {
+    pub token: String,
+    pub expires: SystemTime,
+}
 
 lazy_static! {
     static ref REG_TOKEN: Regex = Regex::new("\"token\":\\s*\"(.+?)\"").unwrap();
 }
 
+/// Strong ETag covering a representation of the current token: the token text
+/// itself for `type=txt`, or the token plus the requested PNG size for `type=png`.
+fn etag_for(token: &str, png_size: u16) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+    png_size.hash(&mut hasher);
+
+    format!("\"{:x}\"", hasher.finish())
+}
+
+/// The `If-None-Match` comparison backing `serve`'s 304 short-circuit, split
+/// out so it can be unit tested without a running `Handler`.
+fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    if_none_match == Some(etag)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum AuthMode {
+    /// Legacy shared-secret mode: the caller must send a matching `X-API-KEY` header.
+    ApiKey { api_key: String },
+    /// Validates a signed `Authorization: Bearer` JWT against a JWKS document.
+    Jwt {
+        jwks_url: String,
+        issuer: String,
+        audience: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct AccountConfig {
+    id: String,
+    /// Static Kakao credentials are optional: an operator who only ever logs
+    /// in interactively through `/admin` has no reason to keep a plaintext
+    /// password in `config.json`.
+    kakao_id: Option<String>,
+    kakao_pw: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SigningConfig {
+    key_path: String,
+    key_id: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct Config {
-    kakao_id: String,
-    kakao_pw: String,
-    api_key:  String,
-    bind:     String,
+    bind: String,
+    /// Shared secret guarding the `/admin` interactive Kakao login website.
+    admin_secret: String,
+    accounts: Vec<AccountConfig>,
+    /// Optional Ed25519 key used to sign token/QR responses; see `/pubkey`.
+    signing: Option<SigningConfig>,
+    #[serde(flatten)]
+    auth: AuthMode,
 }
 
 #[derive(Debug)]
@@ -42,12 +110,38 @@ struct Cache {
     map: HashMap<u16, Arc<Vec<u8>>>,
 }
 
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            expires: SystemTime::now(),
+            token: String::new(),
+            map: HashMap::new(),
+        }
+    }
+}
+
+/// Everything that's specific to a single Kakao identity: its own cookie jar
+/// (so the headless browser keeps one login session per account rather than
+/// sharing cookies across accounts), its own HTTP client bound to that jar,
+/// its own token-update broadcast channel, and its own admin login session.
+pub(crate) struct Account {
+    pub(crate) kakao_id: Option<String>,
+    pub(crate) kakao_pw: Option<String>,
+    pub(crate) cookiejar: Arc<Jar>,
+    client: Client,
+    token_tx: broadcast::Sender<Arc<TokenUpdate>>,
+    pub(crate) admin_session: AdminSession,
+    // Its own lock, rather than a single cache shared by every account, so
+    // one account's slow PNG encode can't stall another account's poll.
+    cache: Mutex<Cache>,
+}
+
 pub struct Handler {
     cfg: Config,
-    client: Client,
-    cookiejar: Arc<Jar>,
+    accounts: HashMap<String, Account>,
     browser: Mutex<Browser>,
-    cache: Mutex<Cache>,
+    jwt_validator: Option<JwtValidator>,
+    signer: Option<Signer>,
 }
 
 impl Handler {
@@ -61,7 +155,7 @@ impl Handler {
         let mut browser_opt_extensions: Vec<&OsStr> = Vec::new();
         browser_opt_extensions.push(OsStr::new(browser_opt_extensions_useragent.as_str()));
 
-        let browser_opt = 
+        let browser_opt =
             LaunchOptionsBuilder::default()
             .sandbox(true)
             .window_size(Some((375, 667)))
@@ -79,25 +173,66 @@ impl Handler {
             reqwest::header::HeaderValue::from_static(USER_AGENT)
         );
 
-        let cookiejar = Arc::new(Jar::default());
-
-        let client =
+        // gzip shrinks the QR-data JSON notably; HTTP/2 is negotiated automatically
+        // over TLS ALPN once the server advertises it, so no extra builder flag is needed.
+        let jwks_client =
             reqwest::ClientBuilder::new()
-            .cookie_provider(cookiejar.clone())
-            .default_headers(client_header)
+            .default_headers(client_header.clone())
+            .gzip(true)
             .build()?;
 
+        let mut accounts = HashMap::new();
+        for account_cfg in cfg.accounts.iter() {
+            let cookiejar = Arc::new(Jar::default());
+
+            let client =
+                reqwest::ClientBuilder::new()
+                .cookie_provider(cookiejar.clone())
+                .default_headers(client_header.clone())
+                .gzip(true)
+                .build()?;
+
+            let (token_tx, _) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
+
+            accounts.insert(
+                account_cfg.id.clone(),
+                Account {
+                    kakao_id: account_cfg.kakao_id.clone(),
+                    kakao_pw: account_cfg.kakao_pw.clone(),
+                    cookiejar,
+                    client,
+                    token_tx,
+                    admin_session: AdminSession::new(),
+                    cache: Mutex::new(Cache::default()),
+                }
+            );
+        }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////////////
+
+        let jwt_validator = match &cfg.auth {
+            AuthMode::Jwt { jwks_url, issuer, audience } => {
+                let validator = JwtValidator::new(jwks_url.clone(), issuer.clone(), audience.clone(), jwks_client.clone());
+                if let Err(err) = validator.refresh().await {
+                    println!("Warning: initial JWKS fetch failed, will retry on first request: {}", err);
+                }
+                Some(validator)
+            }
+            AuthMode::ApiKey { .. } => None,
+        };
+
+        let signer = match &cfg.signing {
+            Some(signing_cfg) => Some(Signer::load(signing_cfg.key_path.as_str(), signing_cfg.key_id.clone())?),
+            None => None,
+        };
+
         Ok(
             Handler {
                 cfg,
-                client,
-                cookiejar : cookiejar.clone(),
+                accounts,
                 browser: Mutex::new(browser),
-                cache: Mutex::new(Cache {
-                    expires: SystemTime::now(),
-                    token: String::new(),
-                    map: HashMap::new(),
-                }),
+                jwt_validator,
+                signer,
             }
         )
     }
@@ -106,7 +241,108 @@ impl Handler {
         return self.cfg.bind.parse::<SocketAddr>();
     }
 
-    pub async fn serve(&self, req: Request<Body>, addr: SocketAddr) -> hyper::Result<Response<Body>> {
+    pub(crate) fn admin_secret(&self) -> &str {
+        self.cfg.admin_secret.as_str()
+    }
+
+    pub(crate) fn browser(&self) -> &Mutex<Browser> {
+        &self.browser
+    }
+
+    pub(crate) fn account(&self, account_id: &str) -> Option<&Account> {
+        self.accounts.get(account_id)
+    }
+
+    /// Splits an incoming request into (account id, remaining route path).
+    /// The account may be selected by a leading path segment (`/<id>/...`) or
+    /// an `account` query parameter; if exactly one account is configured it's
+    /// used as the default so single-account deployments don't need either.
+    fn resolve_account(&self, path: &str, params: &HashMap<String, String>) -> Option<(String, String)> {
+        let mut segments = path.trim_start_matches('/').splitn(2, '/');
+        let first = segments.next().unwrap_or("");
+
+        if self.accounts.contains_key(first) {
+            let rest = segments.next().unwrap_or("");
+            return Some((first.to_string(), format!("/{}", rest)));
+        }
+
+        if let Some(id) = params.get("account") {
+            return self.accounts.contains_key(id).then(|| (id.clone(), path.to_string()));
+        }
+
+        if self.accounts.len() == 1 {
+            return self.accounts.keys().next().map(|id| (id.clone(), path.to_string()));
+        }
+
+        None
+    }
+
+    /// Spawns one background refresh task per configured account. Each task
+    /// regenerates that account's token shortly before it expires and
+    /// broadcasts the new value to its subscribed WebSocket clients, keeping
+    /// token regeneration off the per-request path entirely.
+    pub fn spawn_background_tasks(self: Arc<Self>) {
+        for account_id in self.accounts.keys().cloned().collect::<Vec<_>>() {
+            let handler = self.clone();
+            tokio::spawn(async move {
+                handler.refresh_loop(account_id).await;
+            });
+        }
+    }
+
+    async fn refresh_loop(&self, account_id: String) {
+        let account = self.accounts.get(&account_id).expect("refresh_loop is only spawned for configured accounts");
+
+        loop {
+            let had_token = {
+                let cache = account.cache.lock().await;
+                cache.token != String::default()
+            };
+
+            let mut token = if had_token {
+                self.generate_token(&account_id, false).await.unwrap_or_default()
+            } else {
+                String::default()
+            };
+
+            if token == String::default() {
+                token = match self.generate_token(&account_id, true).await {
+                    Ok(x) => x,
+                    Err(err) => {
+                        println!("Error refreshing token for account {}: {}", account_id, err);
+                        String::default()
+                    }
+                };
+            }
+
+            let now = SystemTime::now();
+            let expires = now + Duration::from_secs_f32(TOKEN_EXPIRES);
+
+            {
+                let mut cache = account.cache.lock().await;
+                cache.map.clear();
+                cache.token = token.clone();
+                cache.expires = expires;
+            }
+
+            let sleep_for = if token != String::default() {
+                let _ = account.token_tx.send(Arc::new(TokenUpdate { token, expires }));
+                expires.duration_since(SystemTime::now()).unwrap_or(Duration::from_secs(1)).checked_sub(TOKEN_REFRESH_MARGIN).unwrap_or(Duration::from_millis(100))
+            } else {
+                Duration::from_secs(1)
+            };
+
+            tokio::time::sleep(sleep_for).await;
+        }
+    }
+
+    /// Subscribes to the stream of rotated tokens for one account, for the
+    /// WebSocket push endpoint.
+    pub(crate) fn subscribe(&self, account_id: &str) -> Option<broadcast::Receiver<Arc<TokenUpdate>>> {
+        self.accounts.get(account_id).map(|account| account.token_tx.subscribe())
+    }
+
+    pub async fn serve(self: Arc<Self>, req: Request<Body>, addr: SocketAddr) -> hyper::Result<Response<Body>> {
         fn resp(status: StatusCode) -> hyper::Result<Response<Body>> {
             Ok(Response::builder().status(status).body(Body::default()).unwrap())
         }
@@ -133,24 +369,74 @@ impl Handler {
             .unwrap_or(addr.ip().to_string());
 
         println!("{} {} {}", remote_addr, req.method(), req.uri());
+
+        ////////////////////////////////////////////////// Public signing key
+
+        if req.method() == Method::GET && req.uri().path() == "/pubkey" {
+            return match &self.signer {
+                Some(signer) => Ok(
+                    Response::builder()
+                    .status(StatusCode::OK)
+                    .header(hyper::header::CONTENT_TYPE, "text/plain")
+                    .header("X-Signature-Key-Id", signer.key_id())
+                    .body(Body::from(signer.public_key_base64()))
+                    .unwrap()
+                ),
+                None => resp(StatusCode::NOT_FOUND),
+            }
+        }
+
+        let (account_id, route_path) = match self.resolve_account(req.uri().path(), &params_get) {
+            Some(x) => x,
+            None => return resp(StatusCode::BAD_REQUEST),
+        };
+
+        if route_path == "/admin" || route_path.starts_with("/admin/") {
+            return admin::handle(self, req, account_id, route_path).await
+        }
+
         if req.method() != Method::GET {
             return resp(StatusCode::NOT_FOUND)
         }
 
-        ////////////////////////////////////////////////// Check Api Key
+        ////////////////////////////////////////////////// Check Auth
 
-        {
-            let x_api_key =
-                headers
-                .get("X-API-KEY")
-                .and_then(|x| x.to_str().ok())
-                .and_then(|x| Some(x.to_string()))
-                .unwrap_or(String::from(""));
+        match &self.cfg.auth {
+            AuthMode::ApiKey { api_key } => {
+                let x_api_key =
+                    headers
+                    .get("X-API-KEY")
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(|x| Some(x.to_string()))
+                    .unwrap_or(String::from(""));
 
-            if x_api_key != self.cfg.api_key {
-                println!("API Key is incorrect. IP: {}", remote_addr);
-                return resp(StatusCode::UNAUTHORIZED)
-            };
+                if &x_api_key != api_key {
+                    println!("API Key is incorrect. IP: {}", remote_addr);
+                    return resp(StatusCode::UNAUTHORIZED)
+                };
+            }
+            AuthMode::Jwt { .. } => {
+                let bearer_token =
+                    headers
+                    .get(hyper::header::AUTHORIZATION)
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(|x| x.strip_prefix("Bearer "));
+
+                let validator = self.jwt_validator.as_ref().expect("jwt_validator is set when auth mode is Jwt");
+
+                match bearer_token {
+                    Some(token) => {
+                        if let Err(err) = validator.verify(token).await {
+                            println!("JWT is invalid. IP: {} ({})", remote_addr, err);
+                            return resp(StatusCode::UNAUTHORIZED)
+                        }
+                    }
+                    None => {
+                        println!("Authorization header is missing or malformed. IP: {}", remote_addr);
+                        return resp(StatusCode::UNAUTHORIZED)
+                    }
+                }
+            }
         }
 
         //////////////////////////////////////////////////
@@ -174,41 +460,57 @@ impl Handler {
             }
         };
 
-        //////////////////////////////////////////////////
+        ////////////////////////////////////////////////// Upgrade to WebSocket
 
-        let mut cache = self.cache.lock().await;
+        if ws::is_upgrade_request(&req) {
+            return ws::handle(self, req, account_id, png_mode, png_size).await;
+        }
 
-        let now = SystemTime::now();
-        if cache.expires < now {
-            cache.map.clear();
+        //////////////////////////////////////////////////
 
-            if cache.token != String::default() {
-                cache.token = self.generate_token(false).await.unwrap_or(String::default());
-            }
+        let account = self.accounts.get(&account_id).expect("resolve_account only returns ids present in self.accounts");
+        let mut cache = account.cache.lock().await;
 
-            if cache.token == String::default() {
-                cache.token = match self.generate_token(true).await {
-                    Ok(x) => x,
-                    Err(err) => {
-                        println!("Error : {}", err);
-                        return resp(StatusCode::INTERNAL_SERVER_ERROR);
-                    }
-                };
-            }
+        if cache.token == String::default() {
+            return resp(StatusCode::SERVICE_UNAVAILABLE)
+        }
 
-            cache.expires = now + Duration::from_secs_f32(TOKEN_EXPIRES);
+        let now = SystemTime::now();
+        let etag = etag_for(cache.token.as_str(), if png_mode { png_size } else { 0 });
+        let max_age = cache.expires.duration_since(now).unwrap_or(Duration::from_secs(0)).as_secs();
+        let cache_control = format!("max-age={}, must-revalidate", max_age);
 
+        let if_none_match =
+            headers
+            .get(hyper::header::IF_NONE_MATCH)
+            .and_then(|x| x.to_str().ok());
+
+        if etag_matches(if_none_match, etag.as_str()) {
+            return Ok(
+                Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(hyper::header::ETAG, etag)
+                .header(hyper::header::CACHE_CONTROL, cache_control)
+                .body(Body::empty())
+                .unwrap()
+            )
         }
 
+        let signature = self.signer.as_ref().map(|signer| (signer.key_id().to_string(), signer.sign(cache.token.as_str())));
+
         if !png_mode {
-            return Ok(
+            let mut builder =
                 Response::builder()
                 .status(StatusCode::OK)
                 .header(hyper::header::CONTENT_TYPE, "text/plain")
-                .header(hyper::header::CACHE_CONTROL, "no-cache, no-store, must-revalidate")
-                .body(Body::from(cache.token.clone()))
-                .unwrap()
-            )
+                .header(hyper::header::CACHE_CONTROL, cache_control)
+                .header(hyper::header::ETAG, etag);
+
+            if let Some((key_id, sig)) = &signature {
+                builder = builder.header("X-Signature", sig.as_str()).header("X-Signature-Key-Id", key_id.as_str());
+            }
+
+            return Ok(builder.body(Body::from(cache.token.clone())).unwrap())
         } else {
             if !cache.map.contains_key(&png_size) {
                 let qrcode = Arc::new(
@@ -224,96 +526,203 @@ impl Handler {
                         },
                     }
                 );
-    
+
                 cache.map.insert(png_size, qrcode.clone());
             }
 
             let qrcode = cache.map.get(&png_size).unwrap().clone();
             let qrcode_vec = Vec::from(qrcode.as_slice());
 
-            return Ok(
+            let mut builder =
                 Response::builder()
                 .status(StatusCode::OK)
                 .header(hyper::header::CONTENT_TYPE, "image/png")
-                .header(hyper::header::CACHE_CONTROL, "no-cache, no-store, must-revalidate")
-                .body(Body::from(qrcode_vec))
-                .unwrap()
-            )
+                .header(hyper::header::CACHE_CONTROL, cache_control)
+                .header(hyper::header::ETAG, etag);
+
+            if let Some((key_id, sig)) = &signature {
+                builder = builder.header("X-Signature", sig.as_str()).header("X-Signature-Key-Id", key_id.as_str());
+            }
+
+            return Ok(builder.body(Body::from(qrcode_vec)).unwrap())
         }
     }
 
-    async fn generate_token(&self, do_login: bool) -> std::result::Result<String, HandlerError> {
+    async fn generate_token(&self, account_id: &str, do_login: bool) -> std::result::Result<String, HandlerError> {
+        let account = self.accounts.get(account_id).ok_or(HandlerError::UnknownAccount)?;
+
         if do_login {
+            self.do_login(account).await?;
+        }
+
+        match self.fetch_qr_data(account).await {
+            // A missing token means the cookie session itself has expired, not a
+            // transient infra blip, so it's the one failure worth burning a fresh
+            // headless-Chrome login on (unless we only just logged in).
+            Err(err @ HandlerError::CannotFindToken) if !do_login => {
+                println!("Account {} session looks expired ({}); logging in again", account_id, err);
+                self.do_login(account).await?;
+                self.fetch_qr_data(account).await
+            }
+            other => other,
+        }
+    }
+
+    async fn do_login(&self, account: &Account) -> std::result::Result<(), HandlerError> {
+        let (kakao_id, kakao_pw) = match (&account.kakao_id, &account.kakao_pw) {
+            (Some(id), Some(pw)) => (id, pw),
+            _ => return Err(HandlerError::NoStaticCredentials),
+        };
+
+        let cookiejar = account.cookiejar.clone();
+
+        // Only the tab creation itself needs the shared browser lock; the rest
+        // of the login (each step with its own up-to-30s timeout) would otherwise
+        // serialize every account's relogin behind this one.
+        let tab = {
             let browser = self.browser.lock().await;
-            let cookiejar = self.cookiejar.clone();
-
-            let tab = browser.new_tab()?;
-            tab.set_default_timeout(std::time::Duration::from_secs(30));
-            tab.enable_response_handling(
-                Box::new(
-                    move |resp_event: ResponseReceivedEventParams, _| {
-                        let url = match url::Url::parse(resp_event.response.url.as_str()) {
-                            Ok(x) => x,
-                            Err(_) => return,
-                        };
-
-                        for (k, v ) in resp_event.response.headers.iter() {
-                            if k.to_lowercase() == "set-cookie" {
-                                cookiejar.add_cookie_str(v, &url);
-                            }
+            browser.new_tab()?
+        };
+
+        tab.set_default_timeout(std::time::Duration::from_secs(30));
+        tab.enable_response_handling(
+            Box::new(
+                move |resp_event: ResponseReceivedEventParams, _| {
+                    let url = match url::Url::parse(resp_event.response.url.as_str()) {
+                        Ok(x) => x,
+                        Err(_) => return,
+                    };
+
+                    for (k, v ) in resp_event.response.headers.iter() {
+                        if k.to_lowercase() == "set-cookie" {
+                            cookiejar.add_cookie_str(v, &url);
                         }
                     }
-                )
-            )?;
-
-            tab.navigate_to("https://accounts.kakao.com/login?continue=https%3A%2F%2Faccounts.kakao.com%2Fweblogin%2Faccount%2Finfo")?;
-            tab.wait_for_element("#login-form")?;
-
-            let js = format!(
-                " \
-                    document.getElementById('id_email_2').value = '{}'; \
-                    document.getElementById('id_password_3').value = '{}'; \
-                ",
-                self.cfg.kakao_id,
-                self.cfg.kakao_pw,
-            );
-            tab.evaluate(js.as_str(), true)?;
-            tab.wait_for_element("form#login-form button.submit")?.click()?;
+                }
+            )
+        )?;
+
+        tab.navigate_to("https://accounts.kakao.com/login?continue=https%3A%2F%2Faccounts.kakao.com%2Fweblogin%2Faccount%2Finfo")?;
+        tab.wait_for_element("#login-form")?;
+
+        let js = format!(
+            " \
+                document.getElementById('id_email_2').value = '{}'; \
+                document.getElementById('id_password_3').value = '{}'; \
+            ",
+            kakao_id,
+            kakao_pw,
+        );
+        tab.evaluate(js.as_str(), true)?;
+        tab.wait_for_element("form#login-form button.submit")?.click()?;
 
-            tab.wait_until_navigated()?;
-        }
+        tab.wait_until_navigated()?;
+
+        Ok(())
+    }
 
-        //////////////////////////////////////////////////////////////////////////////////////////
+    /// Scrapes the QR token and resolves its QR data, retrying each step with
+    /// exponential backoff on a transient status code or a missing token
+    /// (reqwest follows redirects by default, so a stale/expired session
+    /// usually shows up here as a 200 whose body no longer contains a token).
+    async fn fetch_qr_data(&self, account: &Account) -> std::result::Result<String, HandlerError> {
+        let token = Self::retry(TOKEN_FETCH_RETRIES, "qr_check_in", || async {
+            let resp = account.client.get("https://accounts.kakao.com/qr_check_in").send().await?;
 
-        let resp =
-            self
-            .client
-            .get("https://accounts.kakao.com/qr_check_in")
-            .send()
-            .await?;
+            if resp.status() != StatusCode::OK {
+                return Err(HandlerError::BadStatusCode("qr_check_in", resp.status().as_u16()))
+            }
 
-        if resp.status() != StatusCode::OK {
-            return Err(HandlerError::BadStatusCode(resp.status().as_u16()))
-        }
-        let body = resp.bytes().await?;
-        let token_match = REG_TOKEN.captures(body.as_ref()).ok_or(HandlerError::CannotFindToken)?;
-        let token = token_match.get(1).and_then(|x| String::from_utf8(Vec::from(x.as_bytes())).ok()).ok_or(HandlerError::CannotFindToken)?;
-
-        //////////////////////////////////////////////////////////////////////////////////////////
-
-        let resp =
-            self
-            .client
-            .get(format!("https://accounts.kakao.com/qr_check_in/request_qr_data.json?lang=ko&os=ios&webview_v=2&is_under_age=false&token={}", token))
-            .send()
-            .await?;
-
-        #[derive(Debug, Deserialize)]
-        struct QRData {
-            qr_data: String,
+            let body = resp.bytes().await?;
+            let token_match = REG_TOKEN.captures(body.as_ref()).ok_or(HandlerError::CannotFindToken)?;
+            token_match.get(1).and_then(|x| String::from_utf8(Vec::from(x.as_bytes())).ok()).ok_or(HandlerError::CannotFindToken)
+        }).await?;
+
+        Self::retry(TOKEN_FETCH_RETRIES, "request_qr_data.json", || async {
+            let resp =
+                account
+                .client
+                .get(format!("https://accounts.kakao.com/qr_check_in/request_qr_data.json?lang=ko&os=ios&webview_v=2&is_under_age=false&token={}", token))
+                .send()
+                .await?;
+
+            if resp.status() != StatusCode::OK {
+                return Err(HandlerError::BadStatusCode("request_qr_data.json", resp.status().as_u16()))
+            }
+
+            #[derive(Debug, Deserialize)]
+            struct QRData {
+                qr_data: String,
+            }
+            let qr_data: QRData = resp.json().await?;
+
+            Ok(qr_data.qr_data)
+        }).await
+    }
+
+    /// Retries `f` up to `max_attempts` times with exponential backoff,
+    /// logging each failure. Gives up by returning whatever error the final
+    /// attempt produced, so callers can still match on the underlying cause
+    /// (e.g. `HandlerError::CannotFindToken`) rather than a generic timeout.
+    async fn retry<T, F, Fut>(max_attempts: u32, step_name: &str, mut f: F) -> std::result::Result<T, HandlerError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = std::result::Result<T, HandlerError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(x) => return Ok(x),
+                Err(err) => {
+                    attempt += 1;
+                    if attempt >= max_attempts {
+                        println!("{} failed after {} attempts: {}", step_name, attempt, err);
+                        return Err(err)
+                    }
+
+                    println!("{} attempt {} failed ({}), retrying", step_name, attempt, err);
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                }
+            }
         }
-        let qr_data: QRData = resp.json().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etag_is_stable_for_the_same_token_and_size() {
+        assert_eq!(etag_for("abc", 256), etag_for("abc", 256));
+    }
+
+    #[test]
+    fn etag_changes_with_the_token() {
+        assert_ne!(etag_for("abc", 256), etag_for("xyz", 256));
+    }
+
+    #[test]
+    fn etag_changes_with_the_png_size() {
+        assert_ne!(etag_for("abc", 256), etag_for("abc", 512));
+    }
+
+    #[test]
+    fn if_none_match_hits_on_an_exact_etag_match() {
+        let etag = etag_for("abc", 256);
+        assert!(etag_matches(Some(etag.as_str()), etag.as_str()));
+    }
+
+    #[test]
+    fn if_none_match_misses_on_a_stale_etag() {
+        let current = etag_for("abc", 256);
+        let stale = etag_for("abc", 512);
+        assert!(!etag_matches(Some(stale.as_str()), current.as_str()));
+    }
 
-        Ok(qr_data.qr_data)
+    #[test]
+    fn if_none_match_misses_when_absent() {
+        let etag = etag_for("abc", 256);
+        assert!(!etag_matches(None, etag.as_str()));
     }
 }
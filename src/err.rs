@@ -1,7 +1,11 @@
 #[derive(Debug)]
 pub enum HandlerError {
-    BadStatusCode(u16),
+    BadStatusCode(&'static str, u16),
     CannotFindToken,
+    InvalidToken,
+    UnknownKeyId,
+    UnknownAccount,
+    NoStaticCredentials,
     HyperError(hyper::Error),
     ReqwestError(reqwest::Error),
     FailureError(failure::Error),
@@ -10,8 +14,12 @@ pub enum HandlerError {
 impl std::fmt::Display for HandlerError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
-            HandlerError::BadStatusCode(status_code) => write!(f, "qr_check_in returned: {}", status_code),
+            HandlerError::BadStatusCode(endpoint, status_code) => write!(f, "{} returned: {}", endpoint, status_code),
             HandlerError::CannotFindToken => write!(f, "Cannot find token"),
+            HandlerError::InvalidToken => write!(f, "JWT is invalid or expired"),
+            HandlerError::UnknownKeyId => write!(f, "JWKS does not contain the token's kid"),
+            HandlerError::UnknownAccount => write!(f, "No such account is configured"),
+            HandlerError::NoStaticCredentials => write!(f, "Account has no static Kakao credentials configured; log in via /admin instead"),
             HandlerError::HyperError(ref e) => e.fmt(f),
             HandlerError::ReqwestError(ref e) => e.fmt(f),
             HandlerError::FailureError(ref e) => e.fmt(f),
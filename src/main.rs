@@ -1,5 +1,9 @@
+mod admin;
 mod core;
 mod err;
+mod jwt_auth;
+mod signing;
+mod ws;
 
 use std::sync::Arc;
 
@@ -14,6 +18,8 @@ async fn main() -> Fallible<()> {
 
     let bind_addr = handler.bind_addr()?;
 
+    handler.clone().spawn_background_tasks();
+
     ////////////////////////////////////////////////////////////////////////////////////////////////////
 
     let make_service = make_service_fn(
@@ -24,7 +30,7 @@ async fn main() -> Fallible<()> {
                 let addr = addr.clone();
                 Ok::<_, hyper::Error>(service_fn(move |req| {
                     let handler = handler.clone();
-                    async move { handler.clone().serve(req, addr.clone()).await }
+                    async move { handler.serve(req, addr.clone()).await }
                 }))
             }
         }
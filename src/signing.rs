@@ -0,0 +1,36 @@
+use std::fs;
+
+use ed25519_dalek::{Keypair, Signer as _};
+use failure::Fallible;
+
+/// Signs response bodies with a static Ed25519 key so clients can detect a
+/// compromised reverse proxy or cache silently substituting a bogus token/QR.
+pub struct Signer {
+    keypair: Keypair,
+    key_id: String,
+}
+
+impl Signer {
+    pub fn load(key_path: &str, key_id: String) -> Fallible<Self> {
+        let bytes = fs::read(key_path)?;
+        let keypair = Keypair::from_bytes(&bytes)?;
+
+        Ok(Signer { keypair, key_id })
+    }
+
+    pub fn key_id(&self) -> &str {
+        self.key_id.as_str()
+    }
+
+    /// Base64-encoded detached signature over `message` (the raw token text,
+    /// regardless of whether the caller ends up receiving text or a PNG).
+    pub fn sign(&self, message: &str) -> String {
+        let signature = self.keypair.sign(message.as_bytes());
+        base64::encode(signature.to_bytes())
+    }
+
+    /// Base64-encoded raw public key, served at `/pubkey` for clients to pin.
+    pub fn public_key_base64(&self) -> String {
+        base64::encode(self.keypair.public.to_bytes())
+    }
+}
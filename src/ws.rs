@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use hyper::{Body, Request, Response, StatusCode};
+use hyper_tungstenite::tungstenite::Message;
+use tokio::sync::broadcast;
+
+use crate::core::Handler;
+
+/// Returns true if this request is asking to be upgraded to a WebSocket connection.
+pub fn is_upgrade_request(req: &Request<Body>) -> bool {
+    hyper_tungstenite::is_upgrade_request(req)
+}
+
+/// Completes the WebSocket handshake and spawns a task that streams fresh
+/// tokens/QRs to the client as the background refresh loop rotates them.
+pub async fn handle(
+    handler: Arc<Handler>,
+    mut req: Request<Body>,
+    account_id: String,
+    png_mode: bool,
+    png_size: u16,
+) -> hyper::Result<Response<Body>> {
+    let (response, websocket) = match hyper_tungstenite::upgrade(&mut req, None) {
+        Ok(x) => x,
+        Err(_) => return Ok(Response::builder().status(StatusCode::BAD_REQUEST).body(Body::empty()).unwrap()),
+    };
+
+    tokio::spawn(async move {
+        if let Err(err) = push_updates(handler, websocket, account_id, png_mode, png_size).await {
+            println!("Error in websocket connection: {}", err);
+        }
+    });
+
+    Ok(response)
+}
+
+async fn push_updates(
+    handler: Arc<Handler>,
+    websocket: hyper_tungstenite::HyperWebsocket,
+    account_id: String,
+    png_mode: bool,
+    png_size: u16,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut websocket = websocket.await?;
+    let mut updates = match handler.subscribe(&account_id) {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let update = match update {
+                    Ok(x) => x,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                let message = if png_mode {
+                    match qrcode_generator::to_png_to_vec(update.token.as_str(), qrcode_generator::QrCodeEcc::Medium, png_size as usize) {
+                        Ok(png) => Message::text(base64::encode(png)),
+                        Err(err) => {
+                            println!("Error encoding QR for websocket client: {}", err);
+                            continue
+                        }
+                    }
+                } else {
+                    Message::text(update.token.clone())
+                };
+
+                if websocket.send(message).await.is_err() {
+                    break
+                }
+            }
+            msg = websocket.next() => {
+                match msg {
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(_)) => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(())
+}